@@ -1,77 +1,478 @@
 use std::{
+    collections::hash_map::DefaultHasher,
     error::Error,
     fmt,
+    future::Future,
+    hash::{Hash, Hasher},
+    net::SocketAddr,
+    pin::Pin,
     str::FromStr,
+    sync::Arc,
     task::{Context, Poll},
     time::Duration
 };
 
+use prometheus_client::{
+    encoding::text::encode,
+    metrics::{counter::Counter, gauge::Gauge},
+    registry::Registry,
+};
+
 use structopt::StructOpt;
 
 use futures::executor::block_on;
 use futures::stream::StreamExt;
+use futures_timer::Delay;
 
 use libp2p::{
-    core::upgrade, 
-    identity::{self, ed25519}, 
-    floodsub::{self, Floodsub, FloodsubEvent},
-    NetworkBehaviour, 
-    PeerId, 
-    Swarm,
-    swarm::{NetworkBehaviourEventProcess, SwarmBuilder, SwarmEvent},
+    core::{
+        connection::ConnectionId, transport::OrTransport, upgrade, upgrade::DeniedUpgrade,
+        ConnectedPoint,
+    },
+    identity::{self, ed25519},
+    gossipsub::{
+        Gossipsub, GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage, IdentTopic,
+        MessageId, ValidationMode,
+    },
+    multiaddr::Protocol,
+    Multiaddr,
+    NetworkBehaviour,
+    PeerId,
+    bandwidth::{BandwidthLogging, BandwidthSinks},
+    swarm::{
+        behaviour::toggle::Toggle, dial_opts::DialOpts, ConnectionHandlerEvent,
+        ConnectionHandlerUpgrErr, ConnectionLimits, DialError, IntoConnectionHandler, KeepAlive,
+        NetworkBehaviour as NetworkBehaviourTrait, NetworkBehaviourAction,
+        NetworkBehaviourEventProcess, PollParameters, SubstreamProtocol, SwarmBuilder, SwarmEvent,
+    },
     dns::DnsConfig,
     tcp::TcpConfig,
     plaintext,
     Transport,
     yamux::YamuxConfig,
-    relay::{Relay, RelayConfig, new_transport_and_behaviour}
+    relay::v2::{client, relay::{Config as RelayServerConfig, Event as RelayServerEvent, Relay as RelayServer}},
+    dcutr,
+    identify,
 };
 
 // Listen on all interfaces and whatever port the OS assigns
 const DEFAULT_RELAY_ADDRESS: &str = "/ip4/0.0.0.0/tcp/0";
 
-fn main() -> Result<(), Box<dyn Error>> {
+const IDENTIFY_PROTOCOL_VERSION: &str = "/libp2p-relay-test/identify/1.0.0";
+
+mod metrics {
+    use super::*;
+
+    /// Relay activity counters/gauges, registered once and shared with the
+    /// relay event handler so it can increment them as reservations and
+    /// circuits come and go.
+    #[derive(Default)]
+    pub struct RelayMetrics {
+        pub reservations_accepted: Counter,
+        pub reservations_denied: Counter,
+        pub circuits_active: Gauge,
+        pub circuits_closed: Counter,
+        pub connections_opened: Counter,
+        pub connections_closed: Counter,
+        // Total bytes seen on the underlying transport, not broken down per
+        // circuit; populated by `spawn_bandwidth_logger` from the `BandwidthSinks`
+        // totals since the relay event stream itself carries no byte counts.
+        pub bytes_inbound: Gauge,
+        pub bytes_outbound: Gauge,
+    }
 
-    let opt = Opt::from_args();
-    println!("opt: {:?}", opt);
+    impl RelayMetrics {
+        pub fn register(registry: &mut Registry) -> Self {
+            let metrics = Self::default();
+            registry.register(
+                "relay_reservations_accepted",
+                "Total reservation requests accepted",
+                Box::new(metrics.reservations_accepted.clone()),
+            );
+            registry.register(
+                "relay_reservations_denied",
+                "Total reservation requests denied",
+                Box::new(metrics.reservations_denied.clone()),
+            );
+            registry.register(
+                "relay_circuits_active",
+                "Currently open relayed circuits",
+                Box::new(metrics.circuits_active.clone()),
+            );
+            registry.register(
+                "relay_circuits_closed",
+                "Total relayed circuits that have been closed",
+                Box::new(metrics.circuits_closed.clone()),
+            );
+            registry.register(
+                "relay_connections_opened",
+                "Total connections established by this node",
+                Box::new(metrics.connections_opened.clone()),
+            );
+            registry.register(
+                "relay_connections_closed",
+                "Total connections of this node that have been closed",
+                Box::new(metrics.connections_closed.clone()),
+            );
+            registry.register(
+                "relay_bytes_inbound",
+                "Total bytes received over the underlying transport",
+                Box::new(metrics.bytes_inbound.clone()),
+            );
+            registry.register(
+                "relay_bytes_outbound",
+                "Total bytes sent over the underlying transport",
+                Box::new(metrics.bytes_outbound.clone()),
+            );
+            metrics
+        }
+    }
 
-    let local_key: identity::Keypair = generate_ed25519(opt.secret_key_seed);
-    let local_peer_id = PeerId::from(local_key.public());
-    println!("Local peer id: {:?}", local_peer_id);    
+    /// Serve `registry` in the OpenMetrics text format on `address`, one
+    /// connection at a time, until the process exits.
+    pub fn serve(registry: Registry, address: SocketAddr) {
+        std::thread::spawn(move || {
+            let listener = match std::net::TcpListener::bind(address) {
+                Ok(listener) => listener,
+                Err(err) => {
+                    eprintln!("metrics: failed to bind {}: {}", address, err);
+                    return;
+                }
+            };
+            println!("metrics: serving OpenMetrics on http://{}/metrics", address);
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let mut body = String::new();
+                if encode(&mut body, &registry).is_err() {
+                    continue;
+                }
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = std::io::Write::write_all(&mut stream, response.as_bytes());
+            }
+        });
+    }
+}
 
-    let transport = block_on(DnsConfig::system(TcpConfig::new()))?;
+mod redial {
+    use super::*;
 
-    let relay_config = RelayConfig {
-        connection_idle_timeout: Duration::from_secs(60 * 60),
-        ..Default::default()
-    };    
+    const BASE_DELAY: Duration = Duration::from_millis(500);
+    const MAX_DELAY: Duration = Duration::from_secs(5 * 60);
 
-    let (relay_wrapped_transport, relay_behaviour) = new_transport_and_behaviour(
-        relay_config,
-        transport,
-    );    
+    #[derive(Debug)]
+    pub enum Event {
+        Redialing { after: Duration },
+    }
 
-    // Create a Floodsub topic
-    let floodsub_topic = floodsub::Topic::new("chat");    
+    /// A connection handler that never negotiates any substream protocol.
+    /// `redial` only cares about connection lifecycle (established/closed/dial
+    /// failure), so it has nothing to say to the remote on any given
+    /// connection; this stands in for `libp2p::swarm::dummy::ConnectionHandler`
+    /// so that this module isn't tied to a swarm version that has both that
+    /// type and the `event_process` style the rest of this file uses.
+    #[derive(Clone)]
+    pub struct NoopHandler;
+
+    impl libp2p::swarm::ConnectionHandler for NoopHandler {
+        type InEvent = void::Void;
+        type OutEvent = void::Void;
+        type Error = void::Void;
+        type InboundProtocol = DeniedUpgrade;
+        type OutboundProtocol = DeniedUpgrade;
+        type InboundOpenInfo = ();
+        type OutboundOpenInfo = void::Void;
+
+        fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
+            SubstreamProtocol::new(DeniedUpgrade, ())
+        }
 
-    let mut behaviour = MyBehaviour {
-        relay: relay_behaviour,
-        floodsub: Floodsub::new(local_peer_id),
-    };    
+        fn inject_fully_negotiated_inbound(&mut self, protocol: void::Void, _info: Self::InboundOpenInfo) {
+            void::unreachable(protocol)
+        }
 
-    behaviour.floodsub.subscribe(floodsub_topic.clone());
+        fn inject_fully_negotiated_outbound(&mut self, protocol: void::Void, info: Self::OutboundOpenInfo) {
+            void::unreachable(info)
+        }
 
-    let plaintext = plaintext::PlainText2Config {
-        local_public_key: local_key.public(),
-    };    
+        fn inject_event(&mut self, event: Self::InEvent) {
+            void::unreachable(event)
+        }
+
+        fn inject_dial_upgrade_error(
+            &mut self,
+            info: Self::OutboundOpenInfo,
+            _error: ConnectionHandlerUpgrErr<void::Void>,
+        ) {
+            void::unreachable(info)
+        }
+
+        fn connection_keep_alive(&self) -> KeepAlive {
+            KeepAlive::No
+        }
+
+        fn poll(
+            &mut self,
+            _cx: &mut Context<'_>,
+        ) -> Poll<ConnectionHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::OutEvent, Self::Error>>
+        {
+            Poll::Pending
+        }
+    }
+
+    /// Watches the connection to `relay_peer_id` and, should it drop, redials it
+    /// with an exponential backoff: starting at `BASE_DELAY`, doubling on every
+    /// consecutive failure up to `MAX_DELAY`, and resetting to `BASE_DELAY` once
+    /// a connection to the relay succeeds again.
+    pub struct Behaviour {
+        relay_peer_id: PeerId,
+        relay_address: Multiaddr,
+        delay: Duration,
+        timer: Option<Delay>,
+        pending_event: Option<Event>,
+    }
+
+    impl Behaviour {
+        pub fn new(relay_peer_id: PeerId, relay_address: Multiaddr) -> Self {
+            Behaviour {
+                relay_peer_id,
+                relay_address,
+                delay: BASE_DELAY,
+                timer: None,
+                pending_event: None,
+            }
+        }
+
+        fn schedule_redial(&mut self) {
+            let after = self.delay;
+            self.timer = Some(Delay::new(after));
+            self.pending_event = Some(Event::Redialing { after });
+            self.delay = (self.delay * 2).min(MAX_DELAY);
+        }
+    }
+
+    impl NetworkBehaviourTrait for Behaviour {
+        type ConnectionHandler = NoopHandler;
+        type OutEvent = Event;
+
+        fn new_handler(&mut self) -> Self::ConnectionHandler {
+            NoopHandler
+        }
+
+        fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+            if *peer_id == self.relay_peer_id {
+                vec![self.relay_address.clone()]
+            } else {
+                Vec::new()
+            }
+        }
+
+        fn inject_connection_established(
+            &mut self,
+            peer_id: &PeerId,
+            _connection_id: &ConnectionId,
+            _endpoint: &ConnectedPoint,
+            _failed_addresses: Option<&Vec<Multiaddr>>,
+            _other_established: usize,
+        ) {
+            if *peer_id == self.relay_peer_id {
+                self.delay = BASE_DELAY;
+                self.timer = None;
+            }
+        }
 
-    let transport = relay_wrapped_transport
-        .upgrade(upgrade::Version::V1)
-        .authenticate(plaintext)
-        .multiplex(YamuxConfig::default())
-        .boxed();    
+        fn inject_connection_closed(
+            &mut self,
+            peer_id: &PeerId,
+            _connection_id: &ConnectionId,
+            _endpoint: &ConnectedPoint,
+            _handler: <Self::ConnectionHandler as IntoConnectionHandler>::Handler,
+            remaining_established: usize,
+        ) {
+            if *peer_id == self.relay_peer_id && remaining_established == 0 {
+                self.schedule_redial();
+            }
+        }
 
-    let mut swarm = Swarm::new(transport, behaviour, local_peer_id);
+        fn inject_dial_failure(
+            &mut self,
+            peer_id: Option<PeerId>,
+            _handler: Self::ConnectionHandler,
+            _error: &DialError,
+        ) {
+            if peer_id == Some(self.relay_peer_id) {
+                self.schedule_redial();
+            }
+        }
+
+        fn poll(
+            &mut self,
+            cx: &mut Context<'_>,
+            _params: &mut impl PollParameters,
+        ) -> Poll<NetworkBehaviourAction<Self::OutEvent, Self::ConnectionHandler>> {
+            // Surface the "we're about to redial" event before the dial itself so
+            // it shows up in the logs ahead of the resulting `SwarmEvent::Dialing`.
+            if let Some(event) = self.pending_event.take() {
+                return Poll::Ready(NetworkBehaviourAction::GenerateEvent(event));
+            }
+
+            if let Some(timer) = self.timer.as_mut() {
+                if Pin::new(timer).poll(cx).is_ready() {
+                    self.timer = None;
+                    return Poll::Ready(NetworkBehaviourAction::Dial {
+                        opts: DialOpts::peer_id(self.relay_peer_id)
+                            .addresses(vec![self.relay_address.clone()])
+                            .build(),
+                        handler: self.new_handler(),
+                    });
+                }
+            }
+            Poll::Pending
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+
+    let opt = Opt::from_args();
+    println!("opt: {:?}", opt);
+
+    let local_key: identity::Keypair = generate_ed25519(opt.secret_key_seed);
+    let local_peer_id = PeerId::from(local_key.public());
+    println!("Local peer id: {:?}", local_peer_id);    
+
+    let plaintext = plaintext::PlainText2Config {
+        local_public_key: local_key.public(),
+    };
+
+    // In `Relay` mode we only need the server-side behaviour and the plain
+    // transport; in the client modes we additionally need the relay client
+    // transport so that circuit addresses (`.../p2p-circuit/...`) can be
+    // dialed and listened on. `ClientListen` also gets a `redial` behaviour so
+    // that losing the connection to the relay does not leave us unreachable.
+    // Bandwidth logging wraps the raw, pre-upgrade transport: once a transport
+    // has been through `.upgrade().authenticate().multiplex()` its `Output` is
+    // a `(PeerId, StreamMuxerBox)` tuple, not a raw duplex stream, which
+    // `BandwidthConnecLogging` cannot read/write.
+    let (transport, bandwidth_sinks, relay_server, relay_client, redial) = match opt.mode {
+        Mode::Relay => {
+            let relay_server_config = RelayServerConfig {
+                max_reservations: opt.max_reservations,
+                max_circuits: opt.max_circuits,
+                max_circuit_duration: Duration::from_secs(opt.max_circuit_duration_secs),
+                max_circuit_bytes: opt.max_circuit_bytes,
+                ..Default::default()
+            };
+            let (raw_transport, bandwidth_sinks) =
+                BandwidthLogging::new(block_on(DnsConfig::system(TcpConfig::new()))?);
+            let transport = raw_transport
+                .upgrade(upgrade::Version::V1)
+                .authenticate(plaintext)
+                .multiplex(YamuxConfig::default())
+                .boxed();
+            (
+                transport,
+                bandwidth_sinks,
+                Toggle::from(Some(RelayServer::new(local_peer_id, relay_server_config))),
+                Toggle::from(None),
+                Toggle::from(None),
+            )
+        }
+        Mode::ClientListen | Mode::ClientDial => {
+            let (relay_transport, relay_client) =
+                client::Client::new_transport_and_behaviour(local_peer_id);
+            let base_transport = block_on(DnsConfig::system(TcpConfig::new()))?;
+            let (raw_transport, bandwidth_sinks) =
+                BandwidthLogging::new(OrTransport::new(relay_transport, base_transport));
+            let transport = raw_transport
+                .upgrade(upgrade::Version::V1)
+                .authenticate(plaintext)
+                .multiplex(YamuxConfig::default())
+                .boxed();
+            let redial = match opt.mode {
+                Mode::ClientListen => {
+                    let (relay_peer_id, relay_address) =
+                        parse_relay_address(&get_relay_peer_address(&opt))?;
+                    Toggle::from(Some(redial::Behaviour::new(relay_peer_id, relay_address)))
+                }
+                _ => Toggle::from(None),
+            };
+            (
+                transport,
+                bandwidth_sinks,
+                Toggle::from(None),
+                Toggle::from(Some(relay_client)),
+                redial,
+            )
+        }
+    };
+
+    // Create a Gossipsub topic
+    let gossipsub_topic = IdentTopic::new("chat");
+
+    // Hash the message payload so that the same chat message arriving via
+    // several relayed paths is only delivered once.
+    let message_id_fn = |message: &GossipsubMessage| {
+        let mut hasher = DefaultHasher::new();
+        message.data.hash(&mut hasher);
+        MessageId::from(hasher.finish().to_string())
+    };
+
+    let gossipsub_config = GossipsubConfigBuilder::default()
+        .validation_mode(parse_validation_mode(&opt.gossipsub_validation_mode))
+        .heartbeat_interval(Duration::from_secs(opt.gossipsub_heartbeat_interval_secs))
+        .message_id_fn(message_id_fn)
+        .build()
+        .expect("valid gossipsub config");
+
+    let mut gossipsub = Gossipsub::new(
+        libp2p::gossipsub::MessageAuthenticity::Signed(local_key.clone()),
+        gossipsub_config,
+    )
+    .expect("valid gossipsub behaviour");
+    gossipsub.subscribe(&gossipsub_topic)?;
+
+    let metrics = match &opt.metrics_address {
+        Some(address) => {
+            let address: SocketAddr = address.parse()?;
+            let mut registry = Registry::default();
+            let metrics = Arc::new(metrics::RelayMetrics::register(&mut registry));
+            metrics::serve(registry, address);
+            Some(metrics)
+        }
+        None => None,
+    };
+
+    let behaviour = MyBehaviour {
+        relay_server,
+        relay_client,
+        gossipsub,
+        dcutr: dcutr::behaviour::Behaviour::new(),
+        identify: identify::Behaviour::new(identify::Config::new(
+            IDENTIFY_PROTOCOL_VERSION.to_string(),
+            local_key.public(),
+        )),
+        redial,
+        metrics: metrics.clone(),
+    };
+
+    spawn_bandwidth_logger(bandwidth_sinks, Duration::from_secs(30), metrics);
+
+    let connection_limits = ConnectionLimits::default()
+        .with_max_established(opt.max_connections)
+        .with_max_established_per_peer(opt.max_connections_per_peer)
+        .with_max_pending_incoming(opt.max_pending_connections)
+        .with_max_pending_outgoing(opt.max_pending_connections);
+
+    let mut swarm = SwarmBuilder::new(transport, behaviour, local_peer_id)
+        .connection_limits(connection_limits)
+        .build();
 
     match opt.mode {
         Mode::Relay => {
@@ -98,6 +499,18 @@ fn main() -> Result<(), Box<dyn Error>> {
                     SwarmEvent::NewListenAddr { address, .. } => {
                         print_listener_peer(&address, &opt.mode, local_peer_id)
                     }
+                    SwarmEvent::ConnectionEstablished { .. } => {
+                        if let Some(metrics) = swarm.behaviour().metrics.as_ref() {
+                            metrics.connections_opened.inc();
+                        }
+                        println!("{:?}", event);
+                    }
+                    SwarmEvent::ConnectionClosed { .. } => {
+                        if let Some(metrics) = swarm.behaviour().metrics.as_ref() {
+                            metrics.connections_closed.inc();
+                        }
+                        println!("{:?}", event);
+                    }
                     _ => println!("{:?}", event),
                 },
                 Poll::Ready(None) => return Poll::Ready(Ok(())),
@@ -155,31 +568,224 @@ fn get_client_listen_address(opt: &Opt) -> String {
     }
 }
 
+/// Split a relayed listen address such as
+/// `<addr-relay-server>/p2p/<peer-id-relay-server>/p2p-circuit` into the
+/// relay's `PeerId` and the plain address to dial it on, dropping the
+/// trailing `/p2p-circuit` component.
+fn parse_relay_address(address: &str) -> Result<(PeerId, Multiaddr), Box<dyn Error>> {
+    let mut relay_address = Multiaddr::empty();
+    let mut relay_peer_id = None;
+    for protocol in address.parse::<Multiaddr>()?.iter() {
+        match protocol {
+            Protocol::P2pCircuit => break,
+            Protocol::P2p(hash) => {
+                relay_peer_id = Some(PeerId::from_multihash(hash).map_err(|_| "invalid relay peer id in address")?);
+                relay_address.push(Protocol::P2p(hash));
+            }
+            other => relay_address.push(other),
+        }
+    }
+    let relay_peer_id =
+        relay_peer_id.ok_or("relay address must contain a /p2p/<peer-id> component")?;
+    Ok((relay_peer_id, relay_address))
+}
+
+/// Log total inbound/outbound bytes seen on `sinks` every `interval`, for as
+/// long as the process runs.
+fn spawn_bandwidth_logger(
+    sinks: Arc<BandwidthSinks>,
+    interval: Duration,
+    metrics: Option<Arc<metrics::RelayMetrics>>,
+) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        let inbound = sinks.total_inbound();
+        let outbound = sinks.total_outbound();
+        println!("bandwidth: {} bytes in, {} bytes out", inbound, outbound);
+        if let Some(metrics) = &metrics {
+            metrics.bytes_inbound.set(inbound as i64);
+            metrics.bytes_outbound.set(outbound as i64);
+        }
+    });
+}
+
+/// Parse the `--gossipsub-validation-mode` flag, falling back to `Strict`.
+fn parse_validation_mode(mode: &str) -> ValidationMode {
+    match mode {
+        "permissive" => ValidationMode::Permissive,
+        "anonymous" => ValidationMode::Anonymous,
+        "none" => ValidationMode::None,
+        _ => ValidationMode::Strict,
+    }
+}
+
 #[derive(NetworkBehaviour)]
 #[behaviour(event_process = true)]
 struct MyBehaviour {
-    relay: Relay,
-    floodsub: Floodsub,
+    relay_server: Toggle<RelayServer>,
+    relay_client: Toggle<client::Client>,
+    gossipsub: Gossipsub,
+    dcutr: dcutr::behaviour::Behaviour,
+    identify: identify::Behaviour,
+    redial: Toggle<redial::Behaviour>,
+    #[behaviour(ignore)]
+    metrics: Option<Arc<metrics::RelayMetrics>>,
 }
 
-impl NetworkBehaviourEventProcess<FloodsubEvent> for MyBehaviour {
-    // Called when `floodsub` produces an event.
-    fn inject_event(&mut self, message: FloodsubEvent) {
-        if let FloodsubEvent::Message(message) = message {
+impl NetworkBehaviourEventProcess<GossipsubEvent> for MyBehaviour {
+    // Called when `gossipsub` produces an event.
+    fn inject_event(&mut self, event: GossipsubEvent) {
+        if let GossipsubEvent::Message {
+            propagation_source,
+            message,
+            ..
+        } = event
+        {
             println!(
-                "Received: '{:?}' from {:?}",
+                "Received: '{:?}' from {:?} (propagated via {:?})",
                 String::from_utf8_lossy(&message.data),
-                message.source
+                message.source,
+                propagation_source
             );
         }
     }
 }
 
-impl NetworkBehaviourEventProcess<()> for MyBehaviour {
-    // Called when `relay` produces an event.
-    fn inject_event(&mut self, message: ()) {
-        println!("----------This is a test when relay produces an event--------")
-    }    
+impl NetworkBehaviourEventProcess<RelayServerEvent> for MyBehaviour {
+    // Called when `relay_server` produces an event, i.e. whenever a reservation
+    // or circuit request is received, accepted, denied or torn down.
+    fn inject_event(&mut self, event: RelayServerEvent) {
+        match event {
+            RelayServerEvent::ReservationReqAccepted { src_peer_id, .. } => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.reservations_accepted.inc();
+                }
+                println!("relay: accepted reservation from {}", src_peer_id)
+            }
+            RelayServerEvent::ReservationReqDenied { src_peer_id } => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.reservations_denied.inc();
+                }
+                println!("relay: denied reservation from {}", src_peer_id)
+            }
+            RelayServerEvent::CircuitReqAccepted {
+                src_peer_id,
+                dst_peer_id,
+            } => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.circuits_active.inc();
+                }
+                println!(
+                    "relay: opened circuit from {} to {}",
+                    src_peer_id, dst_peer_id
+                )
+            }
+            RelayServerEvent::CircuitReqDenied {
+                src_peer_id,
+                dst_peer_id,
+            } => println!(
+                "relay: denied circuit from {} to {}",
+                src_peer_id, dst_peer_id
+            ),
+            RelayServerEvent::CircuitClosed {
+                src_peer_id,
+                dst_peer_id,
+                ..
+            } => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.circuits_active.dec();
+                    metrics.circuits_closed.inc();
+                }
+                println!(
+                    "relay: closed circuit from {} to {}",
+                    src_peer_id, dst_peer_id
+                )
+            }
+            other => println!("relay: {:?}", other),
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<client::Event> for MyBehaviour {
+    // Called when `relay_client` produces an event.
+    fn inject_event(&mut self, event: client::Event) {
+        match event {
+            client::Event::ReservationReqAccepted { relay_peer_id, .. } => {
+                println!("relay client: reservation accepted by {}", relay_peer_id)
+            }
+            client::Event::OutboundCircuitEstablished { relay_peer_id, .. } => println!(
+                "relay client: outbound circuit established via {}",
+                relay_peer_id
+            ),
+            client::Event::InboundCircuitEstablished { src_peer_id, .. } => println!(
+                "relay client: inbound circuit established from {}",
+                src_peer_id
+            ),
+            other => println!("relay client: {:?}", other),
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<dcutr::behaviour::Event> for MyBehaviour {
+    // Called when `dcutr` produces an event, i.e. whenever a direct connection
+    // upgrade through the relay is attempted, succeeds or fails.
+    fn inject_event(&mut self, event: dcutr::behaviour::Event) {
+        match event {
+            dcutr::behaviour::Event::InitiatedDirectConnectionUpgrade {
+                remote_peer_id,
+                local_relayed_addr,
+            } => println!(
+                "dcutr: initiated hole punch with {}, we are relayed on {}",
+                remote_peer_id, local_relayed_addr
+            ),
+            dcutr::behaviour::Event::RemoteInitiatedDirectConnectionUpgrade {
+                remote_peer_id,
+                local_relayed_addr,
+            } => println!(
+                "dcutr: {} initiated hole punch with us, we are relayed on {}",
+                remote_peer_id, local_relayed_addr
+            ),
+            dcutr::behaviour::Event::DirectConnectionUpgradeSucceeded { remote_peer_id } => {
+                println!(
+                    "dcutr: hole punch with {} succeeded, relayed connection can be closed",
+                    remote_peer_id
+                )
+            }
+            dcutr::behaviour::Event::DirectConnectionUpgradeFailed {
+                remote_peer_id,
+                error,
+            } => println!(
+                "dcutr: hole punch with {} failed: {:?}",
+                remote_peer_id, error
+            ),
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<identify::Event> for MyBehaviour {
+    // Called when `identify` produces an event.
+    fn inject_event(&mut self, event: identify::Event) {
+        // The `identify` behaviour already reports `info.observed_addr` back to the
+        // swarm as an external address via its own `ReportObservedAddr` action; we
+        // only need to surface what we learned about the remote here.
+        if let identify::Event::Received { peer_id, info } = event {
+            println!(
+                "identify: {} observes us at {}, listens on {:?}, supports protocols {:?}",
+                peer_id, info.observed_addr, info.listen_addrs, info.protocols
+            );
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<redial::Event> for MyBehaviour {
+    // Called when `redial` produces an event.
+    fn inject_event(&mut self, event: redial::Event) {
+        match event {
+            redial::Event::Redialing { after } => {
+                println!("redial: relay connection lost, retrying in {:?}", after)
+            }
+        }
+    }
 }
 
 
@@ -255,4 +861,47 @@ struct Opt {
     /// The listening address
     #[structopt(long)]
     address: Option<String>,
+
+    /// Maximum number of simultaneous reservations the relay will grant (relay mode only)
+    #[structopt(long, default_value = "128")]
+    max_reservations: usize,
+
+    /// Maximum number of simultaneous circuits the relay will forward (relay mode only)
+    #[structopt(long, default_value = "16")]
+    max_circuits: usize,
+
+    /// Maximum duration, in seconds, a relayed circuit may stay open (relay mode only)
+    #[structopt(long, default_value = "120")]
+    max_circuit_duration_secs: u64,
+
+    /// Maximum number of bytes that may be forwarded over a single circuit (relay mode only).
+    /// The default budgets for a full gossipsub/identify/DCUtR session, not just a handshake;
+    /// raise it further for long-lived or high-throughput circuits.
+    #[structopt(long, default_value = "1048576")]
+    max_circuit_bytes: u64,
+
+    /// Gossipsub message validation mode (strict, permissive, anonymous, none)
+    #[structopt(long, default_value = "strict")]
+    gossipsub_validation_mode: String,
+
+    /// Gossipsub heartbeat interval, in seconds
+    #[structopt(long, default_value = "1")]
+    gossipsub_heartbeat_interval_secs: u64,
+
+    /// Address to serve OpenMetrics/Prometheus relay metrics on, e.g. 0.0.0.0:9090.
+    /// Metrics are only collected when this is set.
+    #[structopt(long)]
+    metrics_address: Option<String>,
+
+    /// Maximum number of simultaneous connections, across all peers
+    #[structopt(long)]
+    max_connections: Option<u32>,
+
+    /// Maximum number of simultaneous connections to a single peer
+    #[structopt(long)]
+    max_connections_per_peer: Option<u32>,
+
+    /// Maximum number of simultaneous pending (not yet established) connections
+    #[structopt(long)]
+    max_pending_connections: Option<u32>,
 }
\ No newline at end of file